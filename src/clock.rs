@@ -0,0 +1,48 @@
+use std::sync::{Arc, Mutex};
+use time::OffsetDateTime;
+
+/// Supplies the current time as a Unix timestamp.
+pub trait Clock: Send + Sync {
+    fn now_unix(&self) -> i64;
+}
+
+/// The default [`Clock`]: the real system time, in UTC.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_unix(&self) -> i64 {
+        OffsetDateTime::now_utc().unix_timestamp()
+    }
+}
+
+/// A [`Clock`] that only moves when told to, for deterministic tests.
+pub struct MockClock {
+    now: Mutex<i64>,
+}
+
+impl MockClock {
+    pub fn new(now: i64) -> Self {
+        Self { now: Mutex::new(now) }
+    }
+
+    pub fn advance(&self, seconds: i64) {
+        *self.now.lock().unwrap() += seconds;
+    }
+
+    pub fn set(&self, now: i64) {
+        *self.now.lock().unwrap() = now;
+    }
+}
+
+impl Clock for MockClock {
+    fn now_unix(&self) -> i64 {
+        *self.now.lock().unwrap()
+    }
+}
+
+impl<T: Clock + ?Sized> Clock for Arc<T> {
+    fn now_unix(&self) -> i64 {
+        (**self).now_unix()
+    }
+}
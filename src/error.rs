@@ -0,0 +1,52 @@
+use std::fmt;
+
+/// Failure modes of [`crate::Cache::try_insert`] and
+/// [`crate::Cache::try_get`]. Returned instead of panicking, so one
+/// unserializable value or far-future TTL can't take down every other
+/// `Cache` user sharing the store.
+#[derive(Debug)]
+pub enum CacheError {
+    /// The key or value couldn't be encoded in the cache's format.
+    Serialize(String),
+    /// A cached value couldn't be decoded back into the requested type.
+    Deserialize(String),
+    /// `now + ttl` overflowed an `i64` Unix timestamp.
+    TimestampOverflow,
+    /// The file-backed persistence tier failed to write or rename its temp
+    /// file, or to remove the file of an entry evicted for capacity.
+    Io(String),
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CacheError::Serialize(message) => write!(f, "failed to serialize cache entry: {message}"),
+            CacheError::Deserialize(message) => write!(f, "failed to deserialize cache entry: {message}"),
+            CacheError::TimestampOverflow => write!(f, "ttl overflowed the current timestamp"),
+            CacheError::Io(message) => write!(f, "failed to persist cache entry to disk: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for CacheError {}
+
+/// Failure modes of [`crate::Cache::try_get_or_fetch`]: either the cache
+/// lookup/insert failed, or `fetch` itself did.
+#[derive(Debug)]
+pub enum GetOrFetchError<E> {
+    /// The cache lookup or insert failed.
+    Cache(CacheError),
+    /// `fetch` returned an error.
+    Fetch(E),
+}
+
+impl<E: fmt::Display> fmt::Display for GetOrFetchError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GetOrFetchError::Cache(err) => write!(f, "{err}"),
+            GetOrFetchError::Fetch(err) => write!(f, "fetch failed: {err}"),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for GetOrFetchError<E> {}
@@ -0,0 +1,54 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Encodes and decodes cached values, selected as a type parameter on
+/// [`crate::Cache`].
+pub trait Format {
+    fn try_serialize<V: Serialize>(value: &V) -> Result<Vec<u8>, String>;
+    fn try_deserialize<V: DeserializeOwned>(bytes: &[u8]) -> Result<V, String>;
+
+    fn serialize<V: Serialize>(value: &V) -> Vec<u8> {
+        Self::try_serialize(value).unwrap()
+    }
+
+    fn deserialize<V: DeserializeOwned>(bytes: &[u8]) -> V {
+        Self::try_deserialize(bytes).unwrap()
+    }
+}
+
+/// The default format: JSON.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Json;
+
+impl Format for Json {
+    fn try_serialize<V: Serialize>(value: &V) -> Result<Vec<u8>, String> {
+        serde_json::to_vec(value).map_err(|err| err.to_string())
+    }
+
+    fn try_deserialize<V: DeserializeOwned>(bytes: &[u8]) -> Result<V, String> {
+        serde_json::from_slice(bytes).map_err(|err| err.to_string())
+    }
+}
+
+/// A compact binary format, smaller and faster to encode than JSON.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MessagePack;
+
+impl Format for MessagePack {
+    fn try_serialize<V: Serialize>(value: &V) -> Result<Vec<u8>, String> {
+        rmp_serde::to_vec(value).map_err(|err| err.to_string())
+    }
+
+    fn try_deserialize<V: DeserializeOwned>(bytes: &[u8]) -> Result<V, String> {
+        rmp_serde::from_slice(bytes).map_err(|err| err.to_string())
+    }
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    use std::fmt::Write;
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(out, "{:02x}", byte).unwrap();
+    }
+    out
+}
@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// On-disk envelope for a cached value.
+#[derive(Serialize, Deserialize)]
+pub struct FileEntry {
+    pub value: Vec<u8>,
+    pub added_at: i64,
+    pub expire_in: i64,
+}
+
+fn path_for(dir: &Path, cache_key: &str) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    cache_key.hash(&mut hasher);
+    dir.join(format!("{:x}", hasher.finish()))
+}
+
+/// Reads and parses the file for `cache_key`, if present and well-formed.
+pub fn read(dir: &Path, cache_key: &str) -> Option<FileEntry> {
+    let contents = std::fs::read_to_string(path_for(dir, cache_key)).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+/// Removes the file for `cache_key`, if present. A missing file (already
+/// removed, or never written) isn't an error.
+pub fn remove(dir: &Path, cache_key: &str) -> std::io::Result<()> {
+    match std::fs::remove_file(path_for(dir, cache_key)) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+static TMP_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/// Writes `entry` for `cache_key` atomically, via a rename from a
+/// per-writer-unique temp file.
+pub fn write(dir: &Path, cache_key: &str, entry: &FileEntry) -> std::io::Result<()> {
+    let path = path_for(dir, cache_key);
+    let sequence = TMP_SEQUENCE.fetch_add(1, Ordering::Relaxed);
+    let tmp_path = path.with_extension(format!("{}.{sequence}.tmp", std::process::id()));
+    let serialized = serde_json::to_string(entry).unwrap();
+
+    std::fs::write(&tmp_path, serialized)?;
+    std::fs::rename(&tmp_path, &path)
+}
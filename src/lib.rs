@@ -1,61 +1,311 @@
-use lazy_static::lazy_static;
+mod clock;
+mod error;
+mod format;
+mod fs_store;
+mod store;
+
+pub use clock::{Clock, MockClock, SystemClock};
+pub use error::{CacheError, GetOrFetchError};
+pub use format::{Format, Json, MessagePack};
+
 use serde::Serialize;
 use serde::de::DeserializeOwned;
-use std::{collections::HashMap, sync::Mutex};
-use time::ext::NumericalDuration;
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
 use time::OffsetDateTime;
 
 pub struct CacheEntry {
-    value: String,
-    expiring: i64,
+    pub(crate) value: Vec<u8>,
+    pub(crate) expiring: i64,
+    /// Monotonically increasing recency order, bumped on every insert and
+    /// live `get`; eviction orders by it alone. Second-granularity
+    /// timestamps would tie too often to order eviction.
+    pub(crate) access_seq: u64,
 }
 
-lazy_static! {
-    static ref CACHE: Mutex<HashMap<String, CacheEntry>> = Mutex::new(HashMap::new());
+#[derive(Clone)]
+pub struct Cache<Fmt: Format = Json> {
+    prefix: String,
+    dir: Option<PathBuf>,
+    clock: Arc<dyn Clock>,
+    _format: PhantomData<Fmt>,
 }
 
-#[derive(Clone, Debug)]
-pub struct Cache(String);
+impl<Fmt: Format> std::fmt::Debug for Cache<Fmt> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cache")
+            .field("prefix", &self.prefix)
+            .field("dir", &self.dir)
+            .finish()
+    }
+}
 
-impl Cache {
+impl Cache<Json> {
     pub fn new(prefix: &str) -> Self {
-        Self(prefix.to_string())
+        Self::with_format(prefix)
+    }
+
+    /// Like [`Cache::new`], but also persists every entry under `base_dir`.
+    /// The in-memory map is consulted first; the directory is only read on
+    /// a miss.
+    pub fn with_dir(prefix: &str, base_dir: impl Into<PathBuf>) -> std::io::Result<Self> {
+        Self::with_format_and_dir(prefix, base_dir)
     }
 
-    fn get_key<K: Serialize>(&self, key: &K) -> String {
-        format!("{}:{}", self.0, serde_json::to_string(key).unwrap())
+    /// Like [`Cache::new`], but bounds `prefix` to `max_entries`, evicting
+    /// least-recently-used entries on insert once at capacity.
+    pub fn with_capacity(prefix: &str, max_entries: usize) -> Self {
+        store::STORE.set_capacity(prefix, max_entries);
+        Self::new(prefix)
+    }
+}
+
+impl<Fmt: Format> Cache<Fmt> {
+    /// Like [`Cache::new`], but encodes and decodes values with `Fmt`
+    /// instead of the default JSON.
+    pub fn with_format(prefix: &str) -> Self {
+        Self {
+            prefix: prefix.to_string(),
+            dir: None,
+            clock: Arc::new(SystemClock),
+            _format: PhantomData,
+        }
+    }
+
+    /// Like [`Cache::with_dir`], but encodes and decodes values (both in
+    /// memory and on disk) with `Fmt` instead of the default JSON.
+    pub fn with_format_and_dir(
+        prefix: &str,
+        base_dir: impl Into<PathBuf>,
+    ) -> std::io::Result<Self> {
+        let dir = base_dir.into();
+        std::fs::create_dir_all(&dir)?;
+        store::STORE.set_dir(prefix, dir.clone());
+
+        Ok(Self {
+            prefix: prefix.to_string(),
+            dir: Some(dir),
+            clock: Arc::new(SystemClock),
+            _format: PhantomData,
+        })
+    }
+
+    /// Replaces the clock this cache consults for "now", e.g. with a
+    /// [`MockClock`] in tests.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    fn try_get_key<K: Serialize>(&self, key: &K) -> Result<String, CacheError> {
+        let bytes = Fmt::try_serialize(key).map_err(CacheError::Serialize)?;
+        Ok(format!("{}:{}", self.prefix, format::to_hex(&bytes)))
     }
 
     pub fn get<V: DeserializeOwned, K: Serialize>(&self, key: &K) -> Option<V> {
-        let cache = CACHE.lock().unwrap();
-        
-        match cache.get(&self.get_key(key)) {
-            Some(entry) => {
-                if entry.expiring > OffsetDateTime::now_utc().unix_timestamp() {
-                    let result = serde_json::from_str::<V>(&entry.value.clone()).unwrap();
-                    return Some(result);
-                }
-                None
+        self.try_get(key).unwrap()
+    }
+
+    pub fn insert<K: Serialize, V: Serialize>(&self, key: &K, value: &V, ttl: i64) {
+        self.try_insert(key, value, ttl).unwrap()
+    }
+
+    /// Fallible variant of [`Cache::get`]: returns `Err` instead of
+    /// panicking if the cached bytes can't be decoded as `V`.
+    pub fn try_get<V: DeserializeOwned, K: Serialize>(
+        &self,
+        key: &K,
+    ) -> Result<Option<V>, CacheError> {
+        let cache_key = self.try_get_key(key)?;
+        let now = self.clock.now_unix();
+
+        if let Some(value) = store::STORE.get(&cache_key, now) {
+            return Fmt::try_deserialize(&value)
+                .map(Some)
+                .map_err(CacheError::Deserialize);
+        }
+
+        let Some(dir) = self.dir.as_ref() else {
+            return Ok(None);
+        };
+        let Some(entry) = fs_store::read(dir, &cache_key) else {
+            return Ok(None);
+        };
+        if entry.added_at + entry.expire_in <= now {
+            return Ok(None);
+        }
+
+        Fmt::try_deserialize(&entry.value)
+            .map(Some)
+            .map_err(CacheError::Deserialize)
+    }
+
+    /// Fallible variant of [`Cache::insert`]: returns `Err` instead of
+    /// panicking if `value` can't be encoded, or if `now + ttl` overflows an
+    /// `i64` Unix timestamp.
+    pub fn try_insert<K: Serialize, V: Serialize>(
+        &self,
+        key: &K,
+        value: &V,
+        ttl: i64,
+    ) -> Result<(), CacheError> {
+        let cache_key = self.try_get_key(key)?;
+        let now = self.clock.now_unix();
+        let expiring = now.checked_add(ttl).ok_or(CacheError::TimestampOverflow)?;
+        let serialized = Fmt::try_serialize(value).map_err(CacheError::Serialize)?;
+
+        if let Some(dir) = &self.dir {
+            fs_store::write(
+                dir,
+                &cache_key,
+                &fs_store::FileEntry {
+                    value: serialized.clone(),
+                    added_at: now,
+                    expire_in: ttl,
+                },
+            )
+            .map_err(|err| CacheError::Io(err.to_string()))?;
+        }
+
+        store::STORE
+            .insert(
+                &self.prefix,
+                cache_key,
+                CacheEntry {
+                    expiring,
+                    value: serialized,
+                    access_seq: store::next_access_seq(),
+                },
+                now,
+            )
+            .map_err(|err| CacheError::Io(err.to_string()))?;
+
+        Ok(())
+    }
+
+    /// Looks up `key`, returning the cached value on a live hit. On a miss or
+    /// expiry, calls `fetch` to produce the value, inserts it with `ttl`, and
+    /// returns it.
+    ///
+    /// Panics if the cache lookup or insert fails (see [`Cache::try_get`]
+    /// and [`Cache::try_insert`]); use [`Cache::try_get_or_fetch`] with an
+    /// infallible `fetch` to handle that instead.
+    pub fn get_or_fetch<K, V, F>(&self, key: &K, ttl: i64, fetch: F) -> V
+    where
+        K: Serialize,
+        V: Serialize + DeserializeOwned,
+        F: Fetch<V>,
+    {
+        self.try_get_or_fetch::<K, V, _, std::convert::Infallible>(key, ttl, || Ok(fetch.fetch()))
+            .expect("cache operation failed")
+    }
+
+    /// Fallible variant of [`Cache::get_or_fetch`]: `fetch` may fail, in
+    /// which case nothing is inserted, and the cache lookup/insert may fail
+    /// too. A failed lookup skips `fetch`; a failed insert leaves no
+    /// in-memory entry, though with a directory the value's file may
+    /// already be written.
+    pub fn try_get_or_fetch<K, V, F, E>(
+        &self,
+        key: &K,
+        ttl: i64,
+        fetch: F,
+    ) -> Result<V, GetOrFetchError<E>>
+    where
+        K: Serialize,
+        V: Serialize + DeserializeOwned,
+        F: FnOnce() -> Result<V, E>,
+    {
+        if let Some(value) = self.try_get(key).map_err(GetOrFetchError::Cache)? {
+            return Ok(value);
+        }
+
+        let value = fetch().map_err(GetOrFetchError::Fetch)?;
+        self.try_insert(key, &value, ttl)
+            .map_err(GetOrFetchError::Cache)?;
+        Ok(value)
+    }
+}
+
+/// Drops every entry that has expired, across every `Cache` sharing the
+/// global store regardless of prefix or format. A free function rather
+/// than a `Cache` method since it sweeps the whole shared store, not one
+/// prefix.
+pub fn sweep_expired() {
+    store::STORE.sweep_expired(OffsetDateTime::now_utc().unix_timestamp());
+}
+
+/// Handle to the thread spawned by [`spawn_background_sweep`]. Dropping it,
+/// or calling [`stop`](BackgroundSweep::stop), signals the thread to exit.
+pub struct BackgroundSweep {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    handle: Option<std::thread::JoinHandle<()>>,
+}
+
+impl BackgroundSweep {
+    /// Signals the thread to stop and blocks until it exits. Returns
+    /// promptly regardless of `interval`, since the thread waits on a
+    /// condvar rather than sleeping through it.
+    pub fn stop(mut self) {
+        self.signal_stop();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+
+    fn signal_stop(&self) {
+        let (lock, condvar) = &*self.stop;
+        *lock.lock().unwrap() = true;
+        condvar.notify_one();
+    }
+}
+
+impl Drop for BackgroundSweep {
+    fn drop(&mut self) {
+        self.signal_stop();
+    }
+}
+
+/// Spawns a background thread that calls [`sweep_expired`] on `interval`
+/// until the returned [`BackgroundSweep`] is stopped or dropped.
+pub fn spawn_background_sweep(interval: Duration) -> BackgroundSweep {
+    let stop = Arc::new((Mutex::new(false), Condvar::new()));
+    let thread_stop = stop.clone();
+
+    let handle = std::thread::spawn(move || {
+        let (lock, condvar) = &*thread_stop;
+        let mut stopped = lock.lock().unwrap();
+        while !*stopped {
+            let (guard, wait_result) = condvar.wait_timeout(stopped, interval).unwrap();
+            stopped = guard;
+            if *stopped {
+                break;
+            }
+            if wait_result.timed_out() {
+                drop(stopped);
+                sweep_expired();
+                stopped = lock.lock().unwrap();
             }
-            None => None,
         }
+    });
+
+    BackgroundSweep {
+        stop,
+        handle: Some(handle),
     }
+}
 
-    pub fn insert<K: Serialize, V: Serialize>(&self, key: &K, value: &V, ttl: i64) {
-        let mut cache = CACHE.lock().unwrap();
-        let cache_key = self.get_key(key);
-        let expiring = OffsetDateTime::now_utc()
-            .checked_add(ttl.seconds())
-            .unwrap()
-            .unix_timestamp();
-
-        cache.insert(
-            cache_key,
-            CacheEntry {
-                expiring,
-                value: serde_json::to_string(value).unwrap(),
-            },
-        );
+/// Produces a value to populate the cache on a miss. Implemented for any
+/// `Fn() -> V` closure, or on a type for a reusable read-through source.
+pub trait Fetch<V> {
+    fn fetch(&self) -> V;
+}
+
+impl<V, F: Fn() -> V> Fetch<V> for F {
+    fn fetch(&self) -> V {
+        self()
     }
 }
 
@@ -63,7 +313,7 @@ impl Cache {
 #[cfg(test)]
 mod test_cache {
     use serde::{Deserialize, Serialize};
-    use super::Cache;
+    use super::{Cache, CacheError};
 
     const PREFIX: &str = "prefix";
     const KEY: &str = "key";
@@ -85,6 +335,16 @@ mod test_cache {
       assert_eq!(cache.get::<String, _>(&KEY), None);
     }
 
+    #[test]
+    fn message_pack_format() {
+        use super::{Cache as TypedCache, MessagePack};
+
+        let cache = TypedCache::<MessagePack>::with_format(PREFIX);
+        cache.insert(&"msgpack-key", &VALUE, 10);
+
+        assert_eq!(cache.get::<String, _>(&"msgpack-key").unwrap(), VALUE);
+    }
+
     #[test]
     fn payload() {
         #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -115,4 +375,201 @@ mod test_cache {
         assert_eq!(cache.get::<String, _>(&key).unwrap(), VALUE);
     }
 
+    #[test]
+    fn get_or_fetch_hit() {
+        let cache = Cache::new(PREFIX);
+        cache.insert(&KEY, &VALUE, 10);
+
+        let value = cache.get_or_fetch(&KEY, 10, || "other".to_string());
+        assert_eq!(value, VALUE);
+    }
+
+    #[test]
+    fn get_or_fetch_miss() {
+        let cache = Cache::new(PREFIX);
+
+        let value = cache.get_or_fetch(&"missing-key", 10, || VALUE.to_string());
+        assert_eq!(value, VALUE);
+        assert_eq!(cache.get::<String, _>(&"missing-key").unwrap(), VALUE);
+    }
+
+    #[test]
+    fn with_dir_falls_back_to_the_file_on_a_memory_miss() {
+        let dir = std::env::temp_dir().join(format!("cache-test-{}", std::process::id()));
+        let cache = Cache::with_dir(PREFIX, &dir).unwrap();
+
+        // Bypass the in-memory map entirely so only the file backs this entry.
+        crate::fs_store::write(
+            &dir,
+            &cache.try_get_key(&"file-only-key").unwrap(),
+            &crate::fs_store::FileEntry {
+                value: serde_json::to_vec(VALUE).unwrap(),
+                added_at: time::OffsetDateTime::now_utc().unix_timestamp(),
+                expire_in: 10,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(cache.get::<String, _>(&"file-only-key").unwrap(), VALUE);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn mock_clock_advances_past_ttl() {
+        use super::MockClock;
+        use std::sync::Arc;
+
+        let clock = Arc::new(MockClock::new(1_000));
+        let cache = Cache::new(PREFIX).with_clock(clock.clone());
+        cache.insert(&"clocked-key", &VALUE, 5);
+
+        assert_eq!(cache.get::<String, _>(&"clocked-key").unwrap(), VALUE);
+
+        clock.advance(10);
+        assert_eq!(cache.get::<String, _>(&"clocked-key"), None);
+    }
+
+    #[test]
+    fn sweep_expired_drops_expired_entries() {
+        // The entry is live at the cache's mock "now" but expired by the
+        // real clock the sweep uses, so only the sweep can make it vanish.
+        let cache = Cache::new(PREFIX).with_clock(super::MockClock::new(0));
+        cache.insert(&"sweep-key", &VALUE, 1);
+
+        super::sweep_expired();
+
+        assert_eq!(cache.get::<String, _>(&"sweep-key"), None);
+    }
+
+    #[test]
+    fn sweep_expired_also_removes_the_persisted_file() {
+        let dir = std::env::temp_dir().join(format!("cache-test-sweep-dir-{}", std::process::id()));
+        let cache = Cache::with_dir("sweep-dir-prefix", &dir).unwrap();
+        cache.insert(&"sweep-dir-key", &VALUE, -1);
+
+        let cache_key = cache.try_get_key(&"sweep-dir-key").unwrap();
+        assert!(crate::fs_store::read(&dir, &cache_key).is_some());
+
+        super::sweep_expired();
+
+        assert!(crate::fs_store::read(&dir, &cache_key).is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn with_capacity_evicts_the_least_recently_accessed_entry() {
+        let cache = Cache::with_capacity("capacity-prefix", 2);
+        cache.insert(&"cap-a", &VALUE, 60);
+        cache.insert(&"cap-b", &VALUE, 60);
+
+        // Touch "cap-a" so "cap-b" becomes the least-recently-accessed entry.
+        assert_eq!(cache.get::<String, _>(&"cap-a").unwrap(), VALUE);
+
+        cache.insert(&"cap-c", &VALUE, 60);
+
+        assert_eq!(cache.get::<String, _>(&"cap-a").unwrap(), VALUE);
+        assert_eq!(cache.get::<String, _>(&"cap-b"), None);
+        assert_eq!(cache.get::<String, _>(&"cap-c").unwrap(), VALUE);
+    }
+
+    #[test]
+    fn with_capacity_refreshing_an_existing_key_does_not_evict_another() {
+        let cache = Cache::with_capacity("capacity-refresh-prefix", 2);
+        cache.insert(&"cap-a", &VALUE, 60);
+        cache.insert(&"cap-b", &VALUE, 60);
+
+        // Re-inserting "cap-a" doesn't grow the prefix past capacity, so it
+        // shouldn't evict "cap-b".
+        cache.insert(&"cap-a", &"other", 60);
+
+        assert_eq!(cache.get::<String, _>(&"cap-a").unwrap(), "other");
+        assert_eq!(cache.get::<String, _>(&"cap-b").unwrap(), VALUE);
+    }
+
+    #[test]
+    fn with_capacity_eviction_removes_the_persisted_file_too() {
+        let dir = std::env::temp_dir().join(format!("cache-test-capacity-dir-{}", std::process::id()));
+        let prefix = "capacity-dir-prefix";
+
+        // Both bound this prefix's capacity and persist it to disk, the
+        // combination that used to let an "evicted" entry resurrect from
+        // its still-unexpired file.
+        let _ = Cache::with_capacity(prefix, 1);
+        let cache = Cache::with_dir(prefix, &dir).unwrap();
+
+        cache.insert(&"cap-dir-a", &VALUE, 60);
+        cache.insert(&"cap-dir-b", &VALUE, 60);
+
+        assert_eq!(cache.get::<String, _>(&"cap-dir-a"), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn try_insert_reports_io_error_instead_of_silently_dropping_the_write() {
+        let dir = std::env::temp_dir().join(format!("cache-test-io-{}", std::process::id()));
+        let cache = Cache::with_dir(PREFIX, &dir).unwrap();
+
+        // Yank the directory out from under the cache so the write can't
+        // possibly succeed, regardless of what user runs the test.
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let result = cache.try_insert(&"unwritable-key", &VALUE, 10);
+
+        assert!(matches!(result, Err(CacheError::Io(_))));
+    }
+
+    #[test]
+    fn try_get_or_fetch_propagates_error() {
+        use super::GetOrFetchError;
+
+        let cache = Cache::new(PREFIX);
+
+        let result: Result<String, GetOrFetchError<&str>> =
+            cache.try_get_or_fetch(&"missing-key-2", 10, || Err("boom"));
+        assert!(matches!(result, Err(GetOrFetchError::Fetch("boom"))));
+        assert_eq!(cache.get::<String, _>(&"missing-key-2"), None);
+    }
+
+    #[test]
+    fn spawn_background_sweep_runs_and_stops_promptly() {
+        use std::time::{Duration, Instant};
+
+        // Live at the cache's mock "now", expired by the sweep's real clock.
+        let cache = Cache::new("background-sweep-prefix").with_clock(super::MockClock::new(0));
+        cache.insert(&"bg-key", &VALUE, 1);
+
+        let sweep = super::spawn_background_sweep(Duration::from_millis(20));
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(cache.get::<String, _>(&"bg-key"), None);
+        sweep.stop();
+
+        // Stop a sweep mid-wait on a long interval: stop() should return
+        // promptly instead of blocking for the rest of the interval.
+        let long_sweep = super::spawn_background_sweep(Duration::from_secs(60));
+        std::thread::sleep(Duration::from_millis(10));
+        let before_stop = Instant::now();
+        long_sweep.stop();
+        assert!(before_stop.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn try_insert_reports_timestamp_overflow_instead_of_panicking() {
+        let cache = Cache::new(PREFIX);
+
+        let result = cache.try_insert(&"overflow-key", &VALUE, i64::MAX);
+        assert!(matches!(result, Err(CacheError::TimestampOverflow)));
+    }
+
+    #[test]
+    fn try_get_reports_a_type_mismatch_instead_of_panicking() {
+        let cache = Cache::new(PREFIX);
+        cache.insert(&"mismatch-key", &VALUE, 10);
+
+        let result = cache.try_get::<i32, _>(&"mismatch-key");
+        assert!(matches!(result, Err(CacheError::Deserialize(_))));
+    }
+
 }
@@ -0,0 +1,228 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::fs_store;
+use crate::CacheEntry;
+
+const SHARD_COUNT: usize = 16;
+
+/// Recovers the prefix from a `"prefix:hex"` cache key, as built by
+/// `Cache::try_get_key`.
+fn prefix_of(key: &str) -> &str {
+    key.rsplit_once(':').map(|(prefix, _)| prefix).unwrap_or(key)
+}
+
+static ACCESS_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// Next value in the global access-order sequence, which orders entries
+/// by recency even when they were touched within the same second.
+pub fn next_access_seq() -> u64 {
+    ACCESS_SEQ.fetch_add(1, Ordering::Relaxed)
+}
+
+/// The backing store for every [`crate::Cache`], regardless of prefix or
+/// format. Split into independently-locked shards so unrelated keys don't
+/// contend on the same mutex.
+pub struct ShardedStore {
+    shards: Vec<Mutex<HashMap<String, CacheEntry>>>,
+    capacities: Mutex<HashMap<String, usize>>,
+    /// Keys inserted per capacity-bounded prefix, so eviction scans only its
+    /// own candidates. Only populated for prefixes with a capacity. Keys
+    /// dropped by `sweep_expired` stay listed until the next eviction
+    /// treats them as gone.
+    prefix_keys: Mutex<HashMap<String, HashSet<String>>>,
+    /// Per-prefix lock held across the check/evict/insert sequence in
+    /// `insert`, so concurrent inserts into the same capacity-bounded
+    /// prefix can't both slip past the capacity check. Only populated for
+    /// prefixes with a capacity.
+    prefix_locks: Mutex<HashMap<String, Arc<Mutex<()>>>>,
+    /// Persistence directory per prefix backed by [`crate::Cache::with_dir`],
+    /// so eviction and sweep can delete the on-disk file alongside the
+    /// in-memory entry. Only populated for prefixes with a directory.
+    dirs: Mutex<HashMap<String, PathBuf>>,
+}
+
+impl ShardedStore {
+    fn new() -> Self {
+        Self {
+            shards: (0..SHARD_COUNT).map(|_| Mutex::new(HashMap::new())).collect(),
+            capacities: Mutex::new(HashMap::new()),
+            prefix_keys: Mutex::new(HashMap::new()),
+            prefix_locks: Mutex::new(HashMap::new()),
+            dirs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn shard_for(&self, key: &str) -> &Mutex<HashMap<String, CacheEntry>> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+
+    /// Bounds `prefix` to at most `max_entries` entries, evicting
+    /// least-recently-used entries of that prefix on insert once it's
+    /// reached. Unconfigured prefixes stay unbounded.
+    pub fn set_capacity(&self, prefix: &str, max_entries: usize) {
+        self.capacities
+            .lock()
+            .unwrap()
+            .insert(prefix.to_string(), max_entries);
+    }
+
+    /// Records `dir` as the persistence directory for `prefix`, so eviction
+    /// and sweep know which on-disk files to delete alongside the
+    /// in-memory entry.
+    pub fn set_dir(&self, prefix: &str, dir: PathBuf) {
+        self.dirs.lock().unwrap().insert(prefix.to_string(), dir);
+    }
+
+    pub fn get(&self, key: &str, now: i64) -> Option<Vec<u8>> {
+        let mut shard = self.shard_for(key).lock().unwrap();
+        let entry = shard.get_mut(key)?;
+
+        if entry.expiring > now {
+            entry.access_seq = next_access_seq();
+            Some(entry.value.clone())
+        } else {
+            None
+        }
+    }
+
+    fn prefix_lock(&self, prefix: &str) -> Arc<Mutex<()>> {
+        self.prefix_locks
+            .lock()
+            .unwrap()
+            .entry(prefix.to_string())
+            .or_insert_with(|| Arc::new(Mutex::new(())))
+            .clone()
+    }
+
+    pub fn insert(
+        &self,
+        prefix: &str,
+        key: String,
+        entry: CacheEntry,
+        now: i64,
+    ) -> std::io::Result<()> {
+        let capacity = self.capacities.lock().unwrap().get(prefix).copied();
+        if let Some(capacity) = capacity {
+            // Held for the whole check/evict/insert sequence so concurrent
+            // inserts into this prefix can't both observe room below
+            // capacity and both proceed, pushing it over the bound.
+            let prefix_lock = self.prefix_lock(prefix);
+            let _guard = prefix_lock.lock().unwrap();
+
+            // A refresh of an existing key doesn't grow the prefix, so it
+            // shouldn't evict.
+            let exists = self.shard_for(&key).lock().unwrap().contains_key(&key);
+            if !exists {
+                self.evict_to_capacity(prefix, capacity, now)?;
+            }
+
+            self.prefix_keys
+                .lock()
+                .unwrap()
+                .entry(prefix.to_string())
+                .or_default()
+                .insert(key.clone());
+
+            let mut shard = self.shard_for(&key).lock().unwrap();
+            shard.insert(key, entry);
+            return Ok(());
+        }
+
+        let mut shard = self.shard_for(&key).lock().unwrap();
+        shard.insert(key, entry);
+        Ok(())
+    }
+
+    /// Drops every entry whose TTL has elapsed as of `now`. Shards are swept
+    /// one at a time, each lock acquired and released in turn, so the sweep
+    /// never holds a single lock for the whole store. Also removes the
+    /// persisted file backing each expired entry, for prefixes with a
+    /// directory registered, so `with_dir` callers don't leak one file per
+    /// expired key; a removal failure is skipped rather than aborting the
+    /// rest of the sweep.
+    pub fn sweep_expired(&self, now: i64) {
+        for shard in &self.shards {
+            let mut shard = shard.lock().unwrap();
+            let expired: Vec<String> = shard
+                .iter()
+                .filter(|(_, entry)| entry.expiring <= now)
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in &expired {
+                shard.remove(key);
+            }
+            drop(shard);
+
+            for key in expired {
+                if let Some(dir) = self.dirs.lock().unwrap().get(prefix_of(&key)).cloned() {
+                    let _ = fs_store::remove(&dir, &key);
+                }
+            }
+        }
+    }
+
+    /// Makes room for one more entry of `prefix` once it's at capacity:
+    /// drops its already-expired entries first, then its
+    /// least-recently-accessed ones, ordered by `access_seq` rather than a
+    /// timestamp so entries touched within the same second still have a
+    /// deterministic order. Candidates come from `prefix_keys`, not a
+    /// scan of the whole store. Also removes the persisted file for each
+    /// evicted key, if `prefix` has a directory registered, so a
+    /// `with_dir` cache sharing this prefix can't resurrect an "evicted"
+    /// entry from disk.
+    fn evict_to_capacity(&self, prefix: &str, capacity: usize, now: i64) -> std::io::Result<()> {
+        let candidates: Vec<String> = self
+            .prefix_keys
+            .lock()
+            .unwrap()
+            .get(prefix)
+            .map(|keys| keys.iter().cloned().collect())
+            .unwrap_or_default();
+
+        let mut live: Vec<(String, u64)> = Vec::new();
+        let mut gone: Vec<String> = Vec::new();
+
+        for key in candidates {
+            match self.shard_for(&key).lock().unwrap().get(&key) {
+                Some(entry) if entry.expiring > now => live.push((key, entry.access_seq)),
+                _ => gone.push(key),
+            }
+        }
+
+        let target = capacity.saturating_sub(1);
+        if live.len() > target {
+            live.sort_by_key(|(_, access_seq)| *access_seq);
+            let evict_count = live.len() - target;
+            gone.extend(live.into_iter().take(evict_count).map(|(key, _)| key));
+        }
+
+        for key in &gone {
+            self.shard_for(key).lock().unwrap().remove(key);
+        }
+
+        if let Some(keys) = self.prefix_keys.lock().unwrap().get_mut(prefix) {
+            for key in &gone {
+                keys.remove(key);
+            }
+        }
+
+        if let Some(dir) = self.dirs.lock().unwrap().get(prefix).cloned() {
+            for key in &gone {
+                fs_store::remove(&dir, key)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+lazy_static::lazy_static! {
+    pub static ref STORE: ShardedStore = ShardedStore::new();
+}